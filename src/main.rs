@@ -1,28 +1,149 @@
-use flate2::read::GzDecoder;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
 use std::env;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{BufReader, Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
+use std::io::{Read, SeekFrom, Write};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 
 const BUFFER_SIZE: u16 = 4096; // 4 KB
 const CACHE_DIR: &str = "src/cache";
 const LOG_FILE: &str = "src/log.txt";
 const TIMEOUT: u64 = 10; // 10 seconds
 
-fn get_request(stream: &mut TcpStream) -> std::io::Result<String> {
-    // create a buffer to store the request
-    let mut buffer = [0; BUFFER_SIZE as usize];
+// the byte range requested by a client via a `Range: bytes=...` header
+enum Range {
+    // everything from `start` to the end of the file
+    From(u64),
+    // the inclusive `start`-`end` slice
+    Full(u64, u64),
+    // the final `n` bytes of the file
+    Suffix(u64),
+}
+
+// parse a `Range: bytes=...` header, if present, into a Range value
+fn parse_range(request: &str) -> Option<Range> {
+    // find the Range header line, matching the name case-insensitively
+    let value = request.split("\r\n").find_map(|line| {
+        let words: Vec<&str> = line.splitn(2, ": ").collect();
+        if words.len() == 2 && words[0].eq_ignore_ascii_case("Range") {
+            Some(words[1].trim())
+        } else {
+            None
+        }
+    })?;
+
+    // we only understand the `bytes=` unit
+    let spec = value.strip_prefix("bytes=")?;
+
+    // split into the start and end of the range
+    let (start, end) = spec.split_once('-')?;
+    let start = start.trim();
+    let end = end.trim();
+
+    match (start.is_empty(), end.is_empty()) {
+        // "bytes=-n" -> the final n bytes
+        (true, false) => end.parse().ok().map(Range::Suffix),
+        // "bytes=start-" -> from start to the end
+        (false, true) => start.parse().ok().map(Range::From),
+        // "bytes=start-end" -> an explicit slice
+        (false, false) => {
+            let start = start.parse().ok()?;
+            let end = end.parse().ok()?;
+            Some(Range::Full(start, end))
+        }
+        // "bytes=-" is malformed
+        (true, true) => None,
+    }
+}
 
-    // read the request from the stream
-    let _ = stream.read(&mut buffer)?;
+// read from the stream with the per-request timeout applied; a timed-out read
+// surfaces as a TimedOut error so callers can treat it as the end of the body
+async fn read_timed(stream: &mut TcpStream, buffer: &mut [u8]) -> std::io::Result<usize> {
+    match tokio::time::timeout(
+        std::time::Duration::from_secs(TIMEOUT),
+        stream.read(buffer),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "read timed out",
+        )),
+    }
+}
+
+// read the request head and return it alongside any bytes the buffered reader
+// consumed past the blank line; a CONNECT client may pipeline its TLS
+// ClientHello in the same segment, and those bytes must not be dropped
+async fn get_request(stream: &mut TcpStream) -> std::io::Result<(String, Vec<u8>)> {
+    // wrap the stream in a buffered reader so we can read line by line
+    let mut reader = tokio::io::BufReader::new(stream);
+
+    // accumulate the header lines until we hit the blank line that
+    // terminates the request head, so large header blocks aren't truncated
+    let mut request = Vec::new();
+    loop {
+        // read one header line including the trailing newline
+        let mut line = Vec::new();
+        let read = reader.read_until(b'\n', &mut line).await?;
+
+        // if the peer closed the connection, stop reading
+        if read == 0 {
+            break;
+        }
+
+        // remember whether this is the blank line before appending it
+        let is_blank = line == b"\r\n" || line == b"\n";
+
+        // append the line to the request
+        request.extend_from_slice(&line);
+
+        // a blank line marks the end of the header block
+        if is_blank {
+            break;
+        }
+    }
 
     // convert the request to a string
-    let request = String::from_utf8_lossy(&buffer[..]);
+    let request = String::from_utf8_lossy(&request);
 
-    // return the request
-    Ok(request.to_string())
+    // any bytes the reader buffered past the head have already left the socket,
+    // so hand them back to the caller to forward
+    let residual = reader.buffer().to_vec();
+
+    // return the request and the leftover buffered bytes
+    Ok((request.to_string(), residual))
+}
+
+// generate a random 8-character alphanumeric access key, used as a one-time
+// token when the user does not provide their own key on startup
+fn generate_key() -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    // seed a tiny linear congruential generator from the current time
+    let mut state = chrono::Local::now().timestamp_nanos_opt().unwrap_or(0) as u64;
+
+    let mut key = String::new();
+    for _ in 0..8 {
+        // advance the generator and pick a character from the alphabet
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let index = (state >> 33) as usize % ALPHABET.len();
+        key.push(ALPHABET[index] as char);
+    }
+    key
+}
+
+// check that the client presented the shared secret via a `Proxy-Authorization`
+// header; matching the name case-insensitively and comparing the trimmed value
+fn check_auth(request: &str, key: &str) -> bool {
+    request.split("\r\n").any(|line| {
+        let words: Vec<&str> = line.splitn(2, ": ").collect();
+        words.len() == 2
+            && words[0].eq_ignore_ascii_case("Proxy-Authorization")
+            && words[1].trim() == key
+    })
 }
 
 fn check_version(version: &str) -> bool {
@@ -45,6 +166,18 @@ fn open_file(path: &str) -> std::io::Result<File> {
         .expect("Could not open file."))
 }
 
+// the async counterpart of `open_file`, used for the cache so reads and writes
+// don't block the Tokio runtime
+async fn open_cache_file(path: &str) -> std::io::Result<tokio::fs::File> {
+    tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .await
+}
+
 // a function to extract method, url, and version from the request
 fn parse_request(request: &str) -> (String, String, String) {
     // split the request into lines
@@ -56,6 +189,12 @@ fn parse_request(request: &str) -> (String, String, String) {
     // split the first line into words
     let words: Vec<&str> = first_line.split(" ").collect();
 
+    // a well-formed request line has three tokens; anything shorter is
+    // malformed, so report empty fields and let the caller abort the connection
+    if words.len() < 3 {
+        return (String::new(), String::new(), String::new());
+    }
+
     // get the method, url, and version
     let method = words[0].to_string();
     let url = words[1].to_string();
@@ -119,6 +258,191 @@ fn log_request(request: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+// find the first occurrence of `needle` within `haystack`
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// small helper that lets us pull CRLF-terminated lines and exact byte counts
+// out of the socket while holding on to the bytes we have already buffered; a
+// chunk size line or the chunk body may be split across several socket reads
+struct ChunkReader<'a> {
+    stream: &'a mut TcpStream,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(stream: &'a mut TcpStream, prefix: Vec<u8>) -> Self {
+        ChunkReader {
+            stream,
+            buffer: prefix,
+            pos: 0,
+        }
+    }
+
+    // make sure at least `needed` unconsumed bytes are buffered
+    async fn ensure(&mut self, needed: usize) -> std::io::Result<bool> {
+        let mut chunk = [0u8; BUFFER_SIZE as usize];
+        while self.buffer.len() - self.pos < needed {
+            let read = read_timed(self.stream, &mut chunk).await?;
+            if read == 0 {
+                return Ok(false);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+        Ok(true)
+    }
+
+    // read a single CRLF-terminated line, without the trailing CRLF
+    async fn read_line(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut chunk = [0u8; BUFFER_SIZE as usize];
+        loop {
+            if let Some(rel) = find_subslice(&self.buffer[self.pos..], b"\r\n") {
+                let line = self.buffer[self.pos..self.pos + rel].to_vec();
+                self.pos += rel + 2;
+                return Ok(line);
+            }
+
+            let read = read_timed(self.stream, &mut chunk).await?;
+            if read == 0 {
+                // return whatever is left if the peer closed mid-line
+                let line = self.buffer[self.pos..].to_vec();
+                self.pos = self.buffer.len();
+                return Ok(line);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    // consume exactly `count` body bytes
+    async fn read_exact(&mut self, count: usize) -> std::io::Result<Vec<u8>> {
+        self.ensure(count).await?;
+        let end = std::cmp::min(self.pos + count, self.buffer.len());
+        let bytes = self.buffer[self.pos..end].to_vec();
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+// decode a `Transfer-Encoding: chunked` body into its reassembled raw bytes;
+// `prefix` holds the body bytes that were already read alongside the headers
+async fn read_chunked(stream: &mut TcpStream, prefix: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let mut reader = ChunkReader::new(stream, prefix);
+    let mut body = Vec::new();
+
+    loop {
+        // the size line may carry `;`-delimited chunk extensions we ignore
+        let size_line = reader.read_line().await?;
+        let size_text = String::from_utf8_lossy(&size_line);
+        let size_field = size_text.split(';').next().unwrap_or("").trim();
+
+        // a malformed size line ends the body rather than looping forever
+        let size = match usize::from_str_radix(size_field, 16) {
+            Ok(size) => size,
+            Err(_) => break,
+        };
+
+        // a zero-sized chunk terminates the body
+        if size == 0 {
+            break;
+        }
+
+        // read the chunk data followed by its trailing CRLF
+        let data = reader.read_exact(size).await?;
+        body.extend_from_slice(&data);
+        let _ = reader.read_line().await?;
+    }
+
+    // consume any optional trailer headers up to the final blank line
+    loop {
+        let line = reader.read_line().await?;
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    Ok(body)
+}
+
+// read a full HTTP response from the server: the header block is returned as
+// text, the body as raw bytes so binary/compressed payloads stay intact
+async fn read_response(stream: &mut TcpStream) -> std::io::Result<(String, Vec<u8>)> {
+    // growing buffer that holds everything we have read so far
+    let mut data: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; BUFFER_SIZE as usize];
+
+    // keep reading until we have seen the blank line that ends the headers
+    let header_end = loop {
+        if let Some(pos) = find_subslice(&data, b"\r\n\r\n") {
+            break pos;
+        }
+
+        let read = read_timed(stream, &mut chunk).await?;
+        if read == 0 {
+            // connection closed before a complete header block arrived
+            return Ok((String::from_utf8_lossy(&data).to_string(), Vec::new()));
+        }
+        data.extend_from_slice(&chunk[..read]);
+    };
+
+    // split the buffer into the header text and whatever body bytes came with it
+    let headers = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let mut body = data[header_end + 4..].to_vec();
+
+    // look for Content-Length / Transfer-Encoding so we know how to frame the body
+    let mut content_length: Option<usize> = None;
+    let mut chunked = false;
+    for line in headers.split("\r\n") {
+        let words: Vec<&str> = line.split(": ").collect();
+        if words.len() == 2 && words[0].eq_ignore_ascii_case("Content-Length") {
+            content_length = words[1].trim().parse().ok();
+        }
+        if words.len() == 2
+            && words[0].eq_ignore_ascii_case("Transfer-Encoding")
+            && words[1].trim().eq_ignore_ascii_case("chunked")
+        {
+            chunked = true;
+        }
+    }
+
+    // a chunked body is framed by the chunks themselves, decode it and return
+    if chunked {
+        let body = read_chunked(stream, body).await?;
+        return Ok((headers, body));
+    }
+
+    // consume exactly Content-Length bytes when advertised, otherwise read
+    // until the server closes the connection (or the read timeout fires)
+    match content_length {
+        Some(length) => {
+            while body.len() < length {
+                let read = read_timed(stream, &mut chunk).await?;
+                if read == 0 {
+                    break;
+                }
+                body.extend_from_slice(&chunk[..read]);
+            }
+            body.truncate(length);
+        }
+        None => loop {
+            let read = match read_timed(stream, &mut chunk).await {
+                Ok(read) => read,
+                // a timeout simply means the body is complete
+                Err(_) => break,
+            };
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        },
+    }
+
+    Ok((headers, body))
+}
+
 fn check_cache(file_name: &str) -> bool {
     // get the path to the file
     let path = format!("{}/{}", CACHE_DIR, file_name);
@@ -132,17 +456,18 @@ fn check_cache(file_name: &str) -> bool {
     false
 }
 
-fn handle_connection(stream: &mut TcpStream) {
-    // set the timeout for the stream
-    stream
-        .set_read_timeout(Some(std::time::Duration::from_secs(TIMEOUT)))
-        .unwrap();
-
-    // get the request from the stream
-    let request = match get_request(stream) {
-        Ok(request) => request,
-        Err(_) => return,
+async fn handle_connection(mut stream: TcpStream, key: String) {
+    // get the request from the stream, bounded by the per-request timeout
+    let request = match tokio::time::timeout(
+        std::time::Duration::from_secs(TIMEOUT),
+        get_request(&mut stream),
+    )
+    .await
+    {
+        Ok(Ok(request)) => request,
+        _ => return,
     };
+    let (request, residual) = request;
 
     // parse the request
     let (method, url, version) = parse_request(&request);
@@ -159,6 +484,64 @@ fn handle_connection(stream: &mut TcpStream) {
         println!("Error: {}", error);
     }
 
+    // require the shared secret before honoring the request
+    if !check_auth(&request, &key) {
+        println!("Rejected an unauthenticated request for {}.", url);
+        let response = format!(
+            "{} 407 Proxy Authentication Required\r\nProxy-Authenticate: Key\r\n\r\n",
+            version
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+        return;
+    }
+
+    // a CONNECT request asks us to open an opaque tunnel (used for HTTPS); the
+    // payload is encrypted so we cannot cache it, we just shuffle bytes both ways
+    if method == "CONNECT" {
+        // for CONNECT the target is given as `host:port`
+        println!("Opening a tunnel to {}.", url);
+
+        // open a connection to the requested host and port
+        let mut upstream = match TcpStream::connect(&url).await {
+            Ok(upstream) => upstream,
+            Err(_) => {
+                println!("Could not connect to {}.", url);
+                return;
+            }
+        };
+
+        // forward any bytes buffered past the CONNECT head (e.g. a pipelined TLS
+        // ClientHello) so the handshake doesn't stall waiting on lost bytes
+        if !residual.is_empty() {
+            let _ = upstream.write_all(&residual).await;
+        }
+
+        // tell the client the tunnel is ready
+        let _ = stream.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await;
+
+        // split both ends so each direction can be copied on its own task
+        let (mut client_read, mut client_write) = tokio::io::split(stream);
+        let (mut server_read, mut server_write) = tokio::io::split(upstream);
+
+        // copy client -> server and server -> client until either side closes
+        let mut up = tokio::spawn(async move {
+            let _ = tokio::io::copy(&mut client_read, &mut server_write).await;
+        });
+        let mut down = tokio::spawn(async move {
+            let _ = tokio::io::copy(&mut server_read, &mut client_write).await;
+        });
+
+        // as soon as one direction finishes, stop the other so both sockets are
+        // dropped and the tunnel is torn down
+        tokio::select! {
+            _ = &mut up => down.abort(),
+            _ = &mut down => up.abort(),
+        }
+
+        // return
+        return;
+    }
+
     // get server name and file name
     let server_name = get_server_name(&url);
     let file_name = get_file_name(&url);
@@ -187,7 +570,7 @@ fn handle_connection(stream: &mut TcpStream) {
         let path = format!("{}/{}", CACHE_DIR, file_name);
 
         // read the file
-        let file = match open_file(&path) {
+        let mut file = match open_cache_file(&path).await {
             Ok(file) => file,
             Err(_) => {
                 println!("Could not open file.");
@@ -195,21 +578,75 @@ fn handle_connection(stream: &mut TcpStream) {
             }
         };
 
+        // total size of the cached file
+        let total = match file.metadata().await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => {
+                println!("Could not read file metadata.");
+                return;
+            }
+        };
+
+        // if the client asked for a byte range, serve just that slice
+        if let Some(range) = parse_range(&request) {
+            // translate the requested range into an inclusive start-end pair,
+            // clamping the end to the last byte of the file
+            let (start, end) = match range {
+                Range::From(start) => (start, total.saturating_sub(1)),
+                Range::Full(start, end) => (start, std::cmp::min(end, total.saturating_sub(1))),
+                Range::Suffix(n) => (
+                    total.saturating_sub(std::cmp::min(n, total)),
+                    total.saturating_sub(1),
+                ),
+            };
+
+            // a start past the end of the file, or a reversed range that leaves
+            // the clamped end before the start, cannot be satisfied
+            if total == 0 || start >= total || start > end {
+                let response = format!(
+                    "{} 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\n\r\n",
+                    version, total
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                return;
+            }
+
+            // length of the slice we are about to send
+            let length = end - start + 1;
+
+            // create the partial-content response
+            let response = format!(
+                "{} 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                version, start, end, total, length
+            );
+
+            // send the response header
+            let _ = stream.write_all(response.as_bytes()).await;
+
+            // seek to the start of the range and stream only the slice
+            if file.seek(SeekFrom::Start(start)).await.is_ok() {
+                let _ = tokio::io::copy(&mut file.take(length), &mut stream).await;
+            }
+
+            // return
+            return;
+        }
+
         // create the response
         let response = format!("{} 200 OK\r\n\r\n", version);
 
         // send the response
-        let _ = stream.write(response.as_bytes());
+        let _ = stream.write_all(response.as_bytes()).await;
 
         // send the file
-        let _ = std::io::copy(&mut BufReader::new(file), stream);
+        let _ = tokio::io::copy(&mut file, &mut stream).await;
 
         // return
         return;
     }
 
     // create a connection to the server in port 80
-    let mut server_stream = match TcpStream::connect(format!("{}:80", server_name)) {
+    let mut server_stream = match TcpStream::connect(format!("{}:80", server_name)).await {
         Ok(server_stream) => server_stream,
         Err(_) => {
             println!("Could not connect to server: {}", server_name);
@@ -221,42 +658,63 @@ fn handle_connection(stream: &mut TcpStream) {
     let request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: keep-alive\r\nAccept-Encoding: gzip, deflate\r\n\r\n", method, url, server_name);
 
     // send the request to the server
-    let _ = server_stream.write(request.as_bytes());
+    let _ = server_stream.write_all(request.as_bytes()).await;
 
-    // recieve the response from the server
-    let mut response = String::new();
-    let _ = server_stream.read_to_string(&mut response);
+    // recieve the response from the server, keeping the body as raw bytes
+    let (headers, body) = match read_response(&mut server_stream).await {
+        Ok(response) => response,
+        Err(_) => {
+            println!("Could not read the response from the server.");
+            return;
+        }
+    };
 
-    // get the status code
-    let status_code = response.split(" ").collect::<Vec<&str>>()[1];
+    // get the status code, guarding against a status line with no space
+    let status_words: Vec<&str> = headers.split(" ").collect();
+    if status_words.len() < 2 {
+        println!("Malformed status line from the server.");
+        return;
+    }
+    let status_code = status_words[1];
 
     println!("Status code: {}", status_code);
 
     // if status is not 200, return after giving proper message on the terminal
     if status_code != "200" {
         println!("Retrieving the file from the server is unsuccessful. Aborting the connection with status code {}.", status_code);
-        // send the response
-        let _ = stream.write(response.as_bytes());
+
+        // read_response already de-chunked the body, so the upstream framing
+        // headers no longer describe the bytes we are about to send; drop any
+        // Transfer-Encoding / Content-Length lines and restate the real length
+        let mut rewritten = String::new();
+        for line in headers.split("\r\n") {
+            let name = line.split(": ").next().unwrap_or("");
+            if name.eq_ignore_ascii_case("Transfer-Encoding")
+                || name.eq_ignore_ascii_case("Content-Length")
+            {
+                continue;
+            }
+            rewritten.push_str(line);
+            rewritten.push_str("\r\n");
+        }
+        rewritten.push_str(&format!("Content-Length: {}\r\n", body.len()));
+
+        // send the rewritten headers and the de-chunked body back to the client
+        let _ = stream.write_all(rewritten.as_bytes()).await;
+        let _ = stream.write_all(b"\r\n").await;
+        let _ = stream.write_all(&body).await;
 
         // return
         return;
     }
 
-    // separate the response into headers and body
-    let parts: Vec<&str> = response.split("\r\n\r\n").collect();
-
-    // get the headers and body
-    let headers = parts[0];
-    let body = parts[1];
-
-    // get content endoding and content length from the headers
+    // get content encoding from the headers
     let mut content_encoding = "";
-    let mut _content_length = "";
 
     // split the headers into lines
     let lines: Vec<&str> = headers.split("\r\n").collect();
 
-    // get the content encoding and content length
+    // get the content encoding
     for line in lines {
         // split the line into words
         let words: Vec<&str> = line.split(": ").collect();
@@ -264,59 +722,90 @@ fn handle_connection(stream: &mut TcpStream) {
         // get the first word
         let first_word = words[0];
 
-        // check if the first word is content encoding
-        if first_word == "Content-Encoding" {
+        // check if the first word is content encoding, matching case-insensitively
+        if first_word.eq_ignore_ascii_case("Content-Encoding") {
             // get the content encoding
             content_encoding = words[1];
         }
-
-        // check if the first word is content length
-        if first_word == "Content-Length" {
-            // get the content length
-            _content_length = words[1];
-        }
     }
 
-    // if status is 200, download the file using the proper method
-    if content_encoding == "gzip" {
-        // create a decoder
-        let mut decoder = GzDecoder::new(body.as_bytes());
-        let mut buffer = Vec::new(); // create a buffer to store the decoded file
-
-        let _ = decoder.read_to_end(&mut buffer); // read the decoded file
-
-        let path = format!("{}/{}", CACHE_DIR, file_name); // write the file to the cache
-        let mut file = match open_file(&path) {
-            // open the file
-            Ok(file) => file,
-            Err(_) => {
-                println!("Could not open file.");
-                return;
+    // decode the body into its identity representation so the cached and served
+    // bytes are always decompressed; the match is case-insensitive and trims
+    // whitespace, falling back to identity only for truly unencoded bodies. A
+    // decode failure means a corrupt/truncated body, so we bail instead of
+    // caching the partial result and lying to the client with a 200.
+    let decoded = match content_encoding.trim().to_ascii_lowercase().as_str() {
+        "gzip" => {
+            let mut decoder = GzDecoder::new(&body[..]);
+            let mut buffer = Vec::new();
+            decoder.read_to_end(&mut buffer).map(|_| buffer)
+        }
+        "deflate" => {
+            // HTTP `deflate` is zlib-wrapped (RFC 7230/1950); try that first and
+            // fall back to raw DEFLATE for origins that send it unwrapped
+            let mut zlib = ZlibDecoder::new(&body[..]);
+            let mut buffer = Vec::new();
+            match zlib.read_to_end(&mut buffer) {
+                Ok(_) => Ok(buffer),
+                Err(_) => {
+                    let mut raw = DeflateDecoder::new(&body[..]);
+                    let mut buffer = Vec::new();
+                    raw.read_to_end(&mut buffer).map(|_| buffer)
+                }
             }
-        };
-
-        let _ = file.write_all(&buffer); // write the file to the cache
+        }
+        // an unencoded (identity) body is stored as-is
+        "" | "identity" => Ok(body),
+        // any other encoding cannot be decoded here, so it is not cached
+        other => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unsupported content encoding: {}", other),
+        )),
+    };
 
-        let response = format!("{} 200 OK\r\n\r\n", version); // create the response
-        let _ = stream.write(response.as_bytes()); // send the response
-        let _ = stream.write(&buffer); // send the file
-    } else {
-        let path = format!("{}/{}", CACHE_DIR, file_name); // write the file to the cache
-        let mut file = match open_file(&path) {
-            // open the file
-            Ok(file) => file,
-            Err(_) => {
-                println!("Could not open file.");
-                return;
-            }
-        };
+    let buffer = match decoded {
+        Ok(buffer) => buffer,
+        Err(_) => {
+            println!("Could not decode the response body; not caching it.");
+            let response = format!("{} 502 Bad Gateway\r\n\r\n", version);
+            let _ = stream.write_all(response.as_bytes()).await;
+            return;
+        }
+    };
 
-        let _ = file.write_all(body.as_bytes()); // write the file to the cache
+    let path = format!("{}/{}", CACHE_DIR, file_name); // write the file to the cache
+
+    // write the decoded body to a unique temp file and atomically rename it into
+    // place; under the async runtime many connections run in parallel, so two
+    // concurrent misses for the same file must not append over each other and
+    // leave a doubled/garbled cache entry behind
+    let tmp_path = format!(
+        "{}/.{}.{}.tmp",
+        CACHE_DIR,
+        file_name,
+        chrono::Local::now().timestamp_nanos_opt().unwrap_or(0)
+    );
+    let mut file = match tokio::fs::File::create(&tmp_path).await {
+        // open the temp file
+        Ok(file) => file,
+        Err(_) => {
+            println!("Could not open file.");
+            return;
+        }
+    };
 
-        let response = format!("{} 200 OK\r\n\r\n", version); // create the response
-        let _ = stream.write(response.as_bytes()); // send the response
-        let _ = stream.write(body.as_bytes()); // send the file
+    if file.write_all(&buffer).await.is_err() {
+        println!("Could not write to the cache.");
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return;
     }
+    let _ = tokio::fs::rename(&tmp_path, &path).await; // publish the cache entry
+
+    // the stored representation is always decompressed, so the response we send
+    // carries no Content-Encoding header
+    let response = format!("{} 200 OK\r\n\r\n", version); // create the response
+    let _ = stream.write_all(response.as_bytes()).await; // send the response
+    let _ = stream.write_all(&buffer).await; // send the file
 
     println!(
         "File is retrieved successfully with status code {}.",
@@ -327,15 +816,15 @@ fn handle_connection(stream: &mut TcpStream) {
     drop(server_stream);
 
     // return
-    return;
 }
 
-fn main() -> std::io::Result<()> {
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
     let args: Vec<String> = env::args().collect();
 
     // check if the user has provided a port number
     if args.len() < 2 {
-        println!("Usage: cargo run <port>");
+        println!("Usage: cargo run <port> [key]");
         return Ok(());
     }
 
@@ -357,9 +846,18 @@ fn main() -> std::io::Result<()> {
         return Ok(());
     }
 
+    // use the user-provided key, or generate a one-time access token
+    let key = if args.len() >= 3 {
+        args[2].clone()
+    } else {
+        let generated = generate_key();
+        println!("Generated access key: {}", generated);
+        generated
+    };
+
     // create a listener on the port
     let adress = format!("0.0.0.0:{}", port);
-    let listener = match TcpListener::bind(adress) {
+    let listener = match TcpListener::bind(adress).await {
         Ok(listener) => listener,
         Err(_) => {
             println!("Could not bind to port {}, internal error.", port);
@@ -373,7 +871,7 @@ fn main() -> std::io::Result<()> {
 
     loop {
         // accept connections from clients
-        let (mut stream, _) = match listener.accept() {
+        let (stream, _) = match listener.accept().await {
             Ok(stream) => stream,
             Err(_) => {
                 println!("Could not accept connection, internal error.");
@@ -381,14 +879,12 @@ fn main() -> std::io::Result<()> {
             }
         };
 
-        // handle the connection in a new thread
-        thread::spawn(move || {
+        // handle the connection on its own Tokio task
+        let key = key.clone();
+        tokio::spawn(async move {
             println!("-----------------------------");
-            handle_connection(&mut stream);
+            handle_connection(stream, key).await;
             println!("-----------------------------");
         });
-
-        // sleep for 1 second
-        std::thread::sleep(std::time::Duration::from_secs(1));
     }
 }